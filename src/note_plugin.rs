@@ -5,22 +5,41 @@ use bevy::{
     render::view::visibility,
     state::commands,
 };
+use bevy_common_assets::json::JsonAssetPlugin;
+use bevy_hanabi::prelude::*;
+use bevy_tts::Tts;
+use serde::Deserialize;
 
-const HIT_MARGIN: f32 = 0.15;
 const NOTE_SPEED: f32 = 5.0;
 
+// Graded timing windows (seconds from the note's target time) used to rank a hit.
+const MARVELOUS_WINDOW: f32 = 0.022;
+const PERFECT_WINDOW: f32 = 0.045;
+const GREAT_WINDOW: f32 = 0.09;
+const GOOD_WINDOW: f32 = 0.135;
+
 pub struct NotePlugin;
 
 impl Plugin for NotePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (setup, load_song));
+        app.add_plugins(JsonAssetPlugin::<Chart>::new(&["chart.json"]));
+        app.add_plugins(HanabiPlugin);
+        app.add_plugins(bevy_tts::TtsPlugin);
+        app.init_resource::<SongClock>();
+        app.init_resource::<Calibration>();
+        app.init_resource::<Score>();
+        app.add_systems(Startup, (setup, load_song, setup_effects));
+        app.add_systems(Update, spawn_chart);
+        app.add_systems(Update, advance_song_clock);
         app.add_systems(Update, move_notes_down);
         app.add_systems(Update, spawn_notes_from_song);
         app.add_systems(
             Update,
-            (evaluate_notes, hit_note, handle_missed_notes).chain(),
+            (evaluate_notes, hit_note, handle_missed_notes, update_score).chain(),
         );
+        app.add_systems(Update, announce_judgments.after(update_score));
         app.add_systems(Update, illuminate_lane);
+        app.add_systems(Update, (toggle_calibration, run_calibration).chain());
     }
 }
 
@@ -32,6 +51,284 @@ struct Song {
     pub bpm: f32,
 }
 
+#[derive(Asset, TypePath, Debug, Deserialize)]
+struct Chart {
+    bpm: f32,
+    audio: String,
+    notes: Vec<ChartNote>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChartNote {
+    lane: u8,
+    time: f32,
+}
+
+#[derive(Resource)]
+struct ChartHandle(Handle<Chart>);
+
+/// Playback clock that every timing-sensitive system reads instead of `Time`.
+///
+/// `position` tracks the audio track's current position in seconds; `offset_ms`
+/// is a user-configurable latency correction (see the calibration systems). All
+/// gameplay timing is expressed against [`SongClock::now`] so note spawn,
+/// movement, hit windows and misses stay locked to the music rather than to
+/// frame-accumulated wall-clock time.
+#[derive(Resource, Default)]
+struct SongClock {
+    position: f32,
+    offset_ms: f32,
+}
+
+impl SongClock {
+    fn now(&self) -> f32 {
+        self.position + self.offset_ms / 1000.
+    }
+}
+
+/// Marker for the entity that plays the chart's audio track.
+#[derive(Component)]
+struct SongAudio;
+
+/// Metronome tick used by the latency calibration mode.
+#[derive(Resource)]
+struct MetronomeSound(Handle<AudioSource>);
+
+const METRONOME_BPM: f32 = 120.0;
+const CALIBRATION_TAPS: usize = 8;
+
+/// Maps an abstract lane action to one or more physical keys, in the style of
+/// `bevy_input_actionmap`. Lane count is driven by the loaded chart, so the same
+/// resource serves 4K/5K/6K/7K; bindings can be overridden from a settings file.
+#[derive(Resource, Clone)]
+struct KeyBindings {
+    lanes: Vec<Vec<KeyCode>>,
+}
+
+impl KeyBindings {
+    /// Standard osu!mania-style defaults for a given key count, centred on the
+    /// home row with the thumb on `Space` for odd counts.
+    fn default_for(key_count: u8) -> Self {
+        let layout: &[KeyCode] = match key_count {
+            0 | 1 => &[KeyCode::Space],
+            2 => &[KeyCode::KeyF, KeyCode::KeyJ],
+            3 => &[KeyCode::KeyF, KeyCode::Space, KeyCode::KeyJ],
+            4 => &[KeyCode::KeyD, KeyCode::KeyF, KeyCode::KeyJ, KeyCode::KeyK],
+            5 => &[
+                KeyCode::KeyD,
+                KeyCode::KeyF,
+                KeyCode::Space,
+                KeyCode::KeyJ,
+                KeyCode::KeyK,
+            ],
+            6 => &[
+                KeyCode::KeyS,
+                KeyCode::KeyD,
+                KeyCode::KeyF,
+                KeyCode::KeyJ,
+                KeyCode::KeyK,
+                KeyCode::KeyL,
+            ],
+            _ => &[
+                KeyCode::KeyS,
+                KeyCode::KeyD,
+                KeyCode::KeyF,
+                KeyCode::Space,
+                KeyCode::KeyJ,
+                KeyCode::KeyK,
+                KeyCode::KeyL,
+            ],
+        };
+
+        KeyBindings {
+            lanes: layout.iter().map(|key| vec![*key]).collect(),
+        }
+    }
+
+    /// Build bindings for `key_count`, overriding individual lanes from
+    /// `assets/settings.json` when an entry for that key count is present.
+    fn load(key_count: u8) -> Self {
+        let mut bindings = Self::default_for(key_count);
+
+        if let Some(settings) = KeyBindingsSettings::read() {
+            if let Some(modes) = settings.modes.get(&key_count) {
+                for (lane, names) in modes.iter().enumerate() {
+                    if lane >= bindings.lanes.len() {
+                        break;
+                    }
+                    let keys: Vec<KeyCode> = names.iter().filter_map(|n| key_from_name(n)).collect();
+                    if !keys.is_empty() {
+                        bindings.lanes[lane] = keys;
+                    }
+                }
+            }
+        }
+
+        bindings
+    }
+
+    /// Keys bound to `lane`, or an empty slice if the lane is unbound.
+    fn key_for_lane(&self, lane: u8) -> &[KeyCode] {
+        self.lanes.get(lane as usize).map_or(&[], |keys| keys)
+    }
+
+    fn just_pressed(&self, input: &ButtonInput<KeyCode>, lane: u8) -> bool {
+        self.key_for_lane(lane)
+            .iter()
+            .any(|key| input.just_pressed(*key))
+    }
+
+    fn just_released(&self, input: &ButtonInput<KeyCode>, lane: u8) -> bool {
+        self.key_for_lane(lane)
+            .iter()
+            .any(|key| input.just_released(*key))
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyBindingsSettings {
+    /// Per key-count overrides: key names for each lane, indexed by lane.
+    modes: std::collections::HashMap<u8, Vec<Vec<String>>>,
+}
+
+impl KeyBindingsSettings {
+    fn read() -> Option<Self> {
+        let raw = std::fs::read_to_string("assets/settings.json").ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+}
+
+/// Resolve the keys we support in the settings file by their `KeyCode` name.
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    match name {
+        "KeyA" => Some(KeyCode::KeyA),
+        "KeyS" => Some(KeyCode::KeyS),
+        "KeyD" => Some(KeyCode::KeyD),
+        "KeyF" => Some(KeyCode::KeyF),
+        "KeyG" => Some(KeyCode::KeyG),
+        "KeyH" => Some(KeyCode::KeyH),
+        "KeyJ" => Some(KeyCode::KeyJ),
+        "KeyK" => Some(KeyCode::KeyK),
+        "KeyL" => Some(KeyCode::KeyL),
+        "Space" => Some(KeyCode::Space),
+        other => {
+            eprintln!("Unknown key in settings: {other}");
+            None
+        }
+    }
+}
+
+/// Accessibility options read from `assets/settings.json`, letting players who
+/// rely on audio enable spoken judgment feedback (and letting sighted players
+/// switch it off).
+#[derive(Resource, Deserialize)]
+#[serde(default)]
+struct AccessibilitySettings {
+    /// Speak each judgment as it resolves.
+    announcements: bool,
+    /// Speak the current combo every `combo_milestone` resolved notes (0 = off).
+    combo_milestone: u32,
+}
+
+impl Default for AccessibilitySettings {
+    fn default() -> Self {
+        AccessibilitySettings {
+            announcements: true,
+            combo_milestone: 50,
+        }
+    }
+}
+
+impl AccessibilitySettings {
+    fn load() -> Self {
+        std::fs::read_to_string("assets/settings.json")
+            .ok()
+            .and_then(|raw| serde_json::from_str::<SettingsFile>(&raw).ok())
+            .map(|file| file.accessibility)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct SettingsFile {
+    #[serde(default)]
+    accessibility: AccessibilitySettings,
+}
+
+/// Latency calibration state: while `active`, a metronome ticks and every tap of
+/// the calibration key records how far it landed from the nearest beat. Once
+/// enough taps are gathered the average is written back into [`SongClock`].
+#[derive(Resource, Default)]
+struct Calibration {
+    active: bool,
+    /// Wall-clock time calibration was switched on; the beat grid is anchored
+    /// here so it doesn't depend on how long the app has been running.
+    start: f32,
+    next_beat: f32,
+    taps: Vec<f32>,
+}
+
+/// Pre-built particle effects spawned as feedback on hits and misses.
+#[derive(Resource)]
+struct EffectHandles {
+    hit: Handle<EffectAsset>,
+    miss: Handle<EffectAsset>,
+}
+
+/// Build the hit and miss particle effects once at startup and stash their
+/// handles in [`EffectHandles`].
+fn setup_effects(mut commands: Commands, mut effects: ResMut<Assets<EffectAsset>>) {
+    commands.insert_resource(EffectHandles {
+        hit: effects.add(build_burst_effect("hit", 64, 220.)),
+        miss: effects.add(build_burst_effect("miss", 12, 40.)),
+    });
+}
+
+/// A short radial burst used for both hit and miss feedback; the per-particle
+/// colour is driven at spawn time through the `spawn_color` property so hits can
+/// be tinted by judgment quality and misses by a dim grey.
+fn build_burst_effect(name: &str, count: u32, speed: f32) -> EffectAsset {
+    let writer = ExprWriter::new();
+
+    let init_pos = SetPositionCircleModifier {
+        center: writer.lit(Vec3::ZERO).expr(),
+        axis: writer.lit(Vec3::Z).expr(),
+        radius: writer.lit(5.).expr(),
+        dimension: ShapeDimension::Surface,
+    };
+
+    let init_vel = SetVelocityCircleModifier {
+        center: writer.lit(Vec3::ZERO).expr(),
+        axis: writer.lit(Vec3::Z).expr(),
+        speed: writer.lit(speed).expr(),
+    };
+
+    let lifetime = writer.lit(0.4).expr();
+    let init_lifetime = SetAttributeModifier::new(Attribute::LIFETIME, lifetime);
+
+    let color = writer.add_property("spawn_color", 0xffffffffu32.into());
+    let init_color = SetAttributeModifier::new(Attribute::COLOR, writer.prop(color).expr());
+
+    let mut size_gradient = Gradient::new();
+    size_gradient.add_key(0.0, Vec3::splat(8.));
+    size_gradient.add_key(1.0, Vec3::ZERO);
+
+    EffectAsset::new(
+        vec![count],
+        Spawner::once((count as f32).into(), true),
+        writer.finish(),
+    )
+    .with_name(name)
+    .init(init_pos)
+    .init(init_vel)
+    .init(init_lifetime)
+    .init(init_color)
+    .render(SizeOverLifetimeModifier {
+        gradient: size_gradient,
+        screen_space_size: false,
+    })
+}
+
 #[derive(Component)]
 struct NoteData {
     lane: u8,
@@ -47,47 +344,92 @@ struct Note {
 #[derive(Component)]
 struct Spawned;
 
-fn load_song(
+fn load_song(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ChartHandle(asset_server.load("charts/foo.chart.json")));
+    commands.insert_resource(MetronomeSound(asset_server.load("audio/metronome.ogg")));
+    commands.insert_resource(AccessibilitySettings::load());
+    // Seed a default 4K mapping so input systems have a resource from frame one;
+    // `spawn_chart` replaces it once the chart's real key count is known.
+    commands.insert_resource(KeyBindings::load(4));
+}
+
+fn spawn_chart(
     mut commands: Commands,
+    asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    chart_handle: Res<ChartHandle>,
+    charts: Res<Assets<Chart>>,
+    mut spawned: Local<bool>,
 ) {
-    for i in 0..=23 {
+    if *spawned {
+        return;
+    }
+
+    let Some(chart) = charts.get(&chart_handle.0) else {
+        return;
+    };
+
+    println!("Chart loaded, audio: {}", chart.audio);
+    for note in &chart.notes {
         commands.spawn(NoteData {
-            lane: i % 4,
-            time: 4. + i as f32,
+            lane: note.lane,
+            time: note.time,
         });
     }
 
+    // Lane count is driven by the chart's highest lane, so a 7K chart lays out
+    // seven receptors without touching the code.
+    let key_count = chart.notes.iter().map(|note| note.lane).max().unwrap_or(0) + 1;
+    commands.insert_resource(KeyBindings::load(key_count));
+
     let lane_width = 50.;
     let lane_spacing = 50.;
+    let step = 2. * lane_width + lane_spacing;
+    let first = -(key_count as f32 - 1.) / 2. * step;
 
-    for i in 0..=3 {
+    for i in 0..key_count {
         let shape = meshes.add(Circle::new(lane_width));
         let color = materials.add(Color::hsl(0., 0., 0.3));
         commands.spawn((
             Lane(i),
-            Transform::from_xyz(i as f32 * (2. * lane_width + lane_spacing), -500., 0.),
+            Transform::from_xyz(first + i as f32 * step, -500., 0.),
             Mesh2d(shape),
             MeshMaterial2d(color),
         ));
     }
 
-    commands.spawn(Song { bpm: 4.0 });
+    commands.spawn(Song { bpm: chart.bpm });
+    commands.spawn((
+        SongAudio,
+        AudioPlayer::<AudioSource>(asset_server.load(chart.audio.clone())),
+        PlaybackSettings::ONCE,
+    ));
+    *spawned = true;
+}
+
+/// Sync [`SongClock::position`] to the audio sink's real playback position each
+/// frame. Reading the sink (rather than integrating `Time::delta_secs`) keeps
+/// the clock locked to the music even when playback stutters or the frame rate
+/// wobbles, which is the whole point of syncing to audio.
+fn advance_song_clock(mut clock: ResMut<SongClock>, query: Query<&AudioSink, With<SongAudio>>) {
+    if let Ok(sink) = query.get_single() {
+        clock.position = sink.position().as_secs_f32();
+    }
 }
 
 fn spawn_notes_from_song(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    time: Res<Time>,
+    clock: Res<SongClock>,
     mut query: Query<(Entity, &NoteData), Without<Spawned>>,
 ) {
-    let current_time = time.elapsed_secs();
+    let current_time = clock.now();
 
     for (entity, note_data) in &mut query {
         if note_data.time - 5. <= current_time {
-            println!("Spawning note for Entity: {:?}", entity);
+            trace!("Spawning note for Entity: {:?}", entity);
             let shape = meshes.add(Circle::new(50.0));
             let color = materials.add(Color::hsl(250., 0.95, 0.7));
 
@@ -120,11 +462,11 @@ fn setup(mut commands: Commands) {
 }
 
 fn move_notes_down(
-    time: Res<Time>,
+    clock: Res<SongClock>,
     mut query: Query<(&mut Transform, &Note), Without<Lane>>,
     query_lanes: Query<(&Transform, &Lane), Without<Note>>,
 ) {
-    let current_time = time.elapsed_secs();
+    let current_time = clock.now();
 
     for (mut transform, note) in &mut query {
         if let Some((lane_transform, _lane)) = query_lanes
@@ -135,7 +477,7 @@ fn move_notes_down(
             transform.translation.x = hit_position.0;
             transform.translation.y = hit_position.1 - (current_time - note.time) * 200.;
         } else {
-            panic!("Note is in unexpected lane {}", note.lane);
+            warn!("Note is in unexpected lane {}", note.lane);
         }
     }
 }
@@ -143,23 +485,102 @@ fn move_notes_down(
 #[derive(Component)]
 struct IsEvaluable;
 
+/// How accurately a note was struck, from tightest to loosest; `Miss` covers
+/// both late presses outside every window and notes that scrolled past unhit.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Judgment {
+    Marvelous,
+    Perfect,
+    Great,
+    Good,
+    Miss,
+}
+
+impl Judgment {
+    /// Rank a hit by the absolute distance between the press and the note.
+    fn from_offset(offset: f32) -> Self {
+        let offset = offset.abs();
+        if offset < MARVELOUS_WINDOW {
+            Judgment::Marvelous
+        } else if offset < PERFECT_WINDOW {
+            Judgment::Perfect
+        } else if offset < GREAT_WINDOW {
+            Judgment::Great
+        } else if offset < GOOD_WINDOW {
+            Judgment::Good
+        } else {
+            Judgment::Miss
+        }
+    }
+
+    fn points(self) -> u32 {
+        match self {
+            Judgment::Marvelous => 320,
+            Judgment::Perfect => 300,
+            Judgment::Great => 200,
+            Judgment::Good => 100,
+            Judgment::Miss => 0,
+        }
+    }
+
+    /// A combo survives only on Great-or-better; Good and Miss reset it.
+    fn breaks_combo(self) -> bool {
+        matches!(self, Judgment::Good | Judgment::Miss)
+    }
+
+    /// Particle tint for the hit/miss burst.
+    fn burst_color(self) -> u32 {
+        match self {
+            Judgment::Marvelous => 0xffffffff,
+            Judgment::Perfect => 0xff66ffff,
+            Judgment::Great => 0xff66ff66,
+            Judgment::Good => 0xff66ccff,
+            Judgment::Miss => 0x80808080,
+        }
+    }
+}
+
 #[derive(Component)]
 struct HitResult {
     is_hit: bool,
     hit_time: f32,
     offset: f32,
+    judgment: Judgment,
+}
+
+/// Running score derived from the judgment of every resolved note.
+#[derive(Resource, Default)]
+struct Score {
+    points: u64,
+    combo: u32,
+    max_combo: u32,
+    counts: std::collections::HashMap<Judgment, u32>,
+}
+
+impl Score {
+    fn record(&mut self, judgment: Judgment) {
+        self.points += judgment.points() as u64;
+        *self.counts.entry(judgment).or_insert(0) += 1;
+
+        if judgment.breaks_combo() {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.max_combo = self.max_combo.max(self.combo);
+        }
+    }
 }
 
 fn evaluate_notes(
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<SongClock>,
     mut query: Query<(Entity, &Note), (Without<IsEvaluable>, Without<HitResult>)>,
 ) {
-    let current_time = time.elapsed_secs();
+    let current_time = clock.now();
 
     for (entity, note) in &mut query {
-        if (note.time - current_time).abs() < HIT_MARGIN {
-            println!("Marked note as evaluable");
+        if (note.time - current_time).abs() < GOOD_WINDOW {
+            trace!("Marked note as evaluable");
             commands.entity(entity).insert(IsEvaluable);
         }
     }
@@ -168,51 +589,185 @@ fn evaluate_notes(
 fn hit_note(
     mut commands: Commands,
     input: Res<ButtonInput<KeyCode>>,
-    time: Res<Time>,
+    bindings: Res<KeyBindings>,
+    clock: Res<SongClock>,
+    effects: Res<EffectHandles>,
+    lanes: Query<(&Transform, &Lane)>,
     mut query: Query<(Entity, &Note, &mut Visibility), With<IsEvaluable>>,
 ) {
-    let current_time = time.elapsed_secs();
+    let current_time = clock.now();
 
     for (entity, note, mut visibility) in &mut query {
-        println!("HIT NOTE INSIDE FUNCTION");
-
-        let key_code = match note.lane {
-            0 => KeyCode::KeyD,
-            1 => KeyCode::KeyF,
-            2 => KeyCode::KeyJ,
-            3 => KeyCode::KeyK,
-            _ => panic!("Unexpected lane: {}", note.lane),
-        };
-
-        if input.just_pressed(key_code) {
+        if bindings.just_pressed(&input, note.lane) {
+            let offset = current_time - note.time;
+            let judgment = Judgment::from_offset(offset);
             commands.entity(entity).remove::<IsEvaluable>();
             commands.entity(entity).insert(HitResult {
                 is_hit: true,
                 hit_time: current_time,
-                offset: current_time - note.time,
+                offset,
+                judgment,
             });
             *visibility = Visibility::Hidden;
-            println!("TOGGLE Visibility lane {}", note.lane);
+            trace!("Note hit on lane {}", note.lane);
+
+            if let Some((lane_transform, _)) = lanes.iter().find(|(_, lane)| lane.0 == note.lane) {
+                spawn_burst(
+                    &mut commands,
+                    effects.hit.clone(),
+                    lane_transform.translation,
+                    judgment.burst_color(),
+                );
+            }
         }
     }
 }
 
 fn handle_missed_notes(
     mut commands: Commands,
-    time: Res<Time>,
+    clock: Res<SongClock>,
+    effects: Res<EffectHandles>,
+    lanes: Query<(&Transform, &Lane)>,
     mut query: Query<(Entity, &Note), With<IsEvaluable>>,
 ) {
-    let current_time = time.elapsed_secs();
+    let current_time = clock.now();
 
     for (entity, note) in &mut query {
-        if current_time > note.time + HIT_MARGIN {
-            println!("Marked as failed note {}", note.time);
+        if current_time > note.time + GOOD_WINDOW {
+            trace!("Marked as failed note {}", note.time);
             commands.entity(entity).remove::<IsEvaluable>();
             commands.entity(entity).insert(HitResult {
                 is_hit: false,
                 hit_time: current_time,
                 offset: current_time - note.time,
+                judgment: Judgment::Miss,
             });
+
+            if let Some((lane_transform, _)) = lanes.iter().find(|(_, lane)| lane.0 == note.lane) {
+                spawn_burst(
+                    &mut commands,
+                    effects.miss.clone(),
+                    lane_transform.translation,
+                    Judgment::Miss.burst_color(),
+                );
+            }
+        }
+    }
+}
+
+/// Fold every freshly-resolved note into the running [`Score`].
+/// `hit_note`/`handle_missed_notes` insert exactly one [`HitResult`] per note
+/// they resolve, so querying `Added<HitResult>` yields each judgment exactly once.
+fn update_score(mut score: ResMut<Score>, query: Query<&HitResult, Added<HitResult>>) {
+    for hit in &query {
+        score.record(hit.judgment);
+        debug!(
+            "{:?} | combo {} | max {} | score {}",
+            hit.judgment, score.combo, score.max_combo, score.points
+        );
+    }
+}
+
+/// Speak each freshly-resolved judgment (and the occasional combo milestone)
+/// through `bevy_tts` when spoken feedback is enabled, making the game playable
+/// with reduced or no vision. Like [`update_score`] it keys on
+/// `Added<HitResult>`, so every note is announced exactly once.
+fn announce_judgments(
+    settings: Res<AccessibilitySettings>,
+    score: Res<Score>,
+    mut tts: ResMut<Tts>,
+    query: Query<&HitResult, Added<HitResult>>,
+    mut resolved: Local<u32>,
+) {
+    if !settings.announcements {
+        return;
+    }
+
+    for hit in &query {
+        let label = match hit.judgment {
+            Judgment::Marvelous => "Marvelous",
+            Judgment::Perfect => "Perfect",
+            Judgment::Great => "Great",
+            Judgment::Good => "Good",
+            Judgment::Miss => "Miss",
+        };
+        if let Err(err) = tts.speak(label, true) {
+            warn!("Failed to announce judgment: {err}");
+        }
+
+        *resolved += 1;
+        if settings.combo_milestone > 0 && *resolved % settings.combo_milestone == 0 {
+            let _ = tts.speak(format!("{} combo", score.combo), false);
+        }
+    }
+}
+
+/// Spawn a one-shot particle burst at `translation`, tinting the particles via
+/// the effect's `spawn_color` property.
+fn spawn_burst(commands: &mut Commands, handle: Handle<EffectAsset>, translation: Vec3, color: u32) {
+    let mut bundle = ParticleEffectBundle::new(handle);
+    bundle.transform = Transform::from_translation(translation);
+    bundle.properties.set("spawn_color", color.into());
+
+    commands.spawn(bundle);
+}
+
+/// Toggle latency calibration on/off with the `C` key, resetting the tap buffer
+/// and beat cursor each time it is enabled.
+fn toggle_calibration(
+    time: Res<Time>,
+    input: Res<ButtonInput<KeyCode>>,
+    mut calibration: ResMut<Calibration>,
+) {
+    if input.just_pressed(KeyCode::KeyC) {
+        calibration.active = !calibration.active;
+        if calibration.active {
+            let beat = 60. / METRONOME_BPM;
+            calibration.taps.clear();
+            calibration.start = time.elapsed_secs();
+            calibration.next_beat = calibration.start + beat;
+            println!("Calibration started: tap Space on the beat");
+        } else {
+            println!("Calibration cancelled");
+        }
+    }
+}
+
+/// While calibration is active, tick a metronome and record the signed distance
+/// from each Space tap to the nearest beat. After [`CALIBRATION_TAPS`] taps the
+/// averaged distance (in milliseconds) is stored as [`SongClock::offset_ms`].
+fn run_calibration(
+    mut commands: Commands,
+    time: Res<Time>,
+    input: Res<ButtonInput<KeyCode>>,
+    metronome: Res<MetronomeSound>,
+    mut calibration: ResMut<Calibration>,
+    mut clock: ResMut<SongClock>,
+) {
+    if !calibration.active {
+        return;
+    }
+
+    let beat = 60. / METRONOME_BPM;
+    let now = time.elapsed_secs();
+
+    if now >= calibration.next_beat {
+        commands.spawn((
+            AudioPlayer::<AudioSource>(metronome.0.clone()),
+            PlaybackSettings::DESPAWN,
+        ));
+        calibration.next_beat += beat;
+    }
+
+    if input.just_pressed(KeyCode::Space) {
+        let nearest = calibration.start + ((now - calibration.start) / beat).round() * beat;
+        calibration.taps.push(now - nearest);
+
+        if calibration.taps.len() >= CALIBRATION_TAPS {
+            let average = calibration.taps.iter().sum::<f32>() / calibration.taps.len() as f32;
+            clock.offset_ms = average * 1000.;
+            calibration.active = false;
+            println!("Calibration done: offset = {:.1} ms", clock.offset_ms);
         }
     }
 }
@@ -220,40 +775,15 @@ fn handle_missed_notes(
 fn illuminate_lane(
     mut query: Query<(&mut Lane, &mut MeshMaterial2d<ColorMaterial>)>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    bindings: Res<KeyBindings>,
     input: Res<ButtonInput<KeyCode>>,
 ) {
     for (lane, mesh) in &mut query {
         let material = materials.get_mut(&mesh.0).unwrap();
-        match lane.0 {
-            0 => {
-                if input.just_pressed(KeyCode::KeyD) {
-                    material.color = Color::hsl(0., 0., 1.); // Cambia a rojo
-                } else if input.just_released(KeyCode::KeyD) {
-                    material.color = Color::hsl(0., 0., 0.3); // Cambia a color original
-                }
-            }
-            1 => {
-                if input.just_pressed(KeyCode::KeyF) {
-                    material.color = Color::hsl(0., 0., 1.); // Cambia a rojo
-                } else if input.just_released(KeyCode::KeyF) {
-                    material.color = Color::hsl(0., 0., 0.3); // Cambia a color original
-                }
-            }
-            2 => {
-                if input.just_pressed(KeyCode::KeyJ) {
-                    material.color = Color::hsl(0., 0., 1.); // Cambia a rojo
-                } else if input.just_released(KeyCode::KeyJ) {
-                    material.color = Color::hsl(0., 0., 0.3); // Cambia a color original
-                }
-            }
-            3 => {
-                if input.just_pressed(KeyCode::KeyK) {
-                    material.color = Color::hsl(0., 0., 1.); // Cambia a rojo
-                } else if input.just_released(KeyCode::KeyK) {
-                    material.color = Color::hsl(0., 0., 0.3); // Cambia a color original
-                }
-            }
-            _ => panic!("Unexpected lane: {}", lane.0),
+        if bindings.just_pressed(&input, lane.0) {
+            material.color = Color::hsl(0., 0., 1.); // Resalta la lane pulsada
+        } else if bindings.just_released(&input, lane.0) {
+            material.color = Color::hsl(0., 0., 0.3); // Vuelve al color original
         }
     }
 }